@@ -0,0 +1,67 @@
+use std::env;
+
+/// Shared-secret guard for the `/admin/*` endpoints, loaded from `ADMIN_TOKEN`.
+/// When unset, admin endpoints refuse every request — there is no sensible
+/// default secret, and an unauthenticated policy override is worse than no
+/// endpoint at all.
+#[derive(Clone, Default)]
+pub struct AdminAuth {
+    token: Option<String>,
+}
+
+impl AdminAuth {
+    /// Build the guard from the `ADMIN_TOKEN` environment variable.
+    pub fn from_env() -> Self {
+        Self {
+            token: env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+        }
+    }
+
+    /// Check a caller-supplied token against the configured secret. For a
+    /// token of the secret's length the comparison takes the same time
+    /// wherever the bytes differ; a token of another length is rejected
+    /// early, so the secret's length is not hidden.
+    pub fn verify(&self, provided: &str) -> bool {
+        match &self.token {
+            Some(expected) => constant_time_eq(expected.as_bytes(), provided.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_when_no_token_configured() {
+        let auth = AdminAuth { token: None };
+        assert!(!auth.verify("anything"));
+        assert!(!auth.verify(""));
+    }
+
+    #[test]
+    fn accepts_matching_token() {
+        let auth = AdminAuth {
+            token: Some("s3cret".to_string()),
+        };
+        assert!(auth.verify("s3cret"));
+    }
+
+    #[test]
+    fn rejects_mismatched_token() {
+        let auth = AdminAuth {
+            token: Some("s3cret".to_string()),
+        };
+        assert!(!auth.verify("wrong"));
+        assert!(!auth.verify("s3cre"));
+        assert!(!auth.verify("s3cret "));
+    }
+}
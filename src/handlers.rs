@@ -1,14 +1,16 @@
+use crate::admin::AdminAuth;
+use crate::policy::{Policy, PolicyStore};
+use crate::proxy::{resolve_forwarded, TrustedProxies};
 use crate::rate_limiter::RateLimiter;
 use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use tracing:: info;
 
+/// The body is accepted for backwards compatibility but no longer determines
+/// the limit — the tier is an attribute of the identity, looked up server-side.
 #[derive(Deserialize)]
-pub struct CheckRequest {
-    limit: i64,
-    #[serde(default)]
-    burst: Option<i64>,
-}
+pub struct CheckRequest {}
 
 #[derive(Serialize)]
 pub struct CheckResponse {
@@ -17,17 +19,34 @@ pub struct CheckResponse {
     retry_after_ms: i64,
 }
 
+#[derive(Deserialize)]
+pub struct SetPolicyRequest {
+    client_id: String,
+    requests_per_minute: i64,
+    burst: i64,
+}
+
 pub async fn check_rate_limit(
     req: HttpRequest,
-    body: web::Json<CheckRequest>,
+    _body: Option<web::Json<CheckRequest>>,
     limiter: web::Data<RateLimiter>,
+    policies: web::Data<PolicyStore>,
+    trusted: web::Data<TrustedProxies>,
 ) -> HttpResponse {
-    let client_id = extract_client_id(&req);
-    let burst = body.burst. unwrap_or(body.limit);
+    let client_id = extract_client_id(&req, &trusted);
+    let policy = policies.get(&client_id);
 
-    info!(client_id = %client_id, limit = body.limit, burst = burst, "Rate limit check");
+    info!(
+        client_id = %client_id,
+        limit = policy.requests_per_minute,
+        burst = policy.burst,
+        "Rate limit check"
+    );
 
-    match limiter.check(&client_id, body.limit, burst).await {
+    match limiter
+        .check(&client_id, policy.requests_per_minute, policy.burst)
+        .await
+    {
         Ok(result) => {
             let status = if result.allowed { 200 } else { 429 };
             HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap()).json(
@@ -48,21 +67,174 @@ pub async fn check_rate_limit(
 }
 
 pub async fn health(limiter: web::Data<RateLimiter>) -> HttpResponse {
-    match limiter.ping().await {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({"status": "ok"})),
-        Err(_) => HttpResponse::ServiceUnavailable().json(serde_json::json!({"status": "unhealthy"})),
+    if limiter.is_healthy() {
+        HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+    } else {
+        HttpResponse::ServiceUnavailable().json(serde_json::json!({"status": "unhealthy"}))
+    }
+}
+
+pub async fn set_policy(
+    req: HttpRequest,
+    body: web::Json<SetPolicyRequest>,
+    policies: web::Data<PolicyStore>,
+    admin_auth: web::Data<AdminAuth>,
+) -> HttpResponse {
+    let provided = req
+        .headers()
+        .get("X-Admin-Token")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if !admin_auth.verify(provided) {
+        return HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Unauthorized"
+        }));
+    }
+
+    let policy = Policy {
+        requests_per_minute: body.requests_per_minute,
+        burst: body.burst,
+    };
+    match policies.set(&body.client_id, policy).await {
+        Ok(_) => {
+            info!(
+                client_id = %body.client_id,
+                requests_per_minute = policy.requests_per_minute,
+                burst = policy.burst,
+                "Policy updated"
+            );
+            HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+        }
+        Err(e) => {
+            tracing::error!("Redis error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+
+pub async fn cardinality(limiter: web::Data<RateLimiter>) -> HttpResponse {
+    match limiter.cardinality().await {
+        Ok(c) => HttpResponse::Ok().json(serde_json::json!({
+            "unique_clients_last_minute": c.last_minute,
+            "unique_clients_last_hour": c.last_hour,
+        })),
+        Err(e) => {
+            tracing::error!("Redis error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Internal server error"
+            }))
+        }
     }
 }
 
-fn extract_client_id(req: &HttpRequest) -> String {
+fn extract_client_id(req: &HttpRequest, trusted: &TrustedProxies) -> String {
     if let Some(api_key) = req.headers().get("X-API-Key") {
         if let Ok(key) = api_key. to_str() {
             return key. to_string();
         }
     }
 
+    if let Some(peer) = req.peer_addr().map(|addr| addr.ip()) {
+        let chain = forwarded_chain(req);
+        if let Some(client) = resolve_forwarded(trusted, peer, &chain) {
+            return client.to_string();
+        }
+        return peer.to_string();
+    }
+
     req.connection_info()
         .realip_remote_addr()
         .unwrap_or("unknown")
         .to_string()
+}
+
+/// Collect the forwarded address chain (oldest first) from the RFC 7239
+/// `Forwarded` header if present, otherwise `X-Forwarded-For`.
+fn forwarded_chain(req: &HttpRequest) -> Vec<IpAddr> {
+    if let Some(value) = req.headers().get("Forwarded") {
+        if let Ok(raw) = value.to_str() {
+            let chain: Vec<IpAddr> = raw
+                .split(',')
+                .filter_map(parse_forwarded_element)
+                .collect();
+            if !chain.is_empty() {
+                return chain;
+            }
+        }
+    }
+
+    req.headers()
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .map(|raw| {
+            raw.split(',')
+                .filter_map(|part| part.trim().parse::<IpAddr>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Extract the `for=` address from a single `Forwarded` header element,
+/// stripping optional quoting, brackets, and a trailing port.
+fn parse_forwarded_element(element: &str) -> Option<IpAddr> {
+    let for_part = element
+        .split(';')
+        .map(str::trim)
+        .find_map(|pair| pair.strip_prefix("for=").or_else(|| pair.strip_prefix("For=")))?;
+    let for_part = for_part.trim_matches('"');
+    let for_part = for_part.strip_prefix('[').unwrap_or(for_part);
+    // IPv6 literals are bracketed; a bare IPv4 may carry a `:port` suffix.
+    if let Some((addr, _)) = for_part.split_once(']') {
+        addr.parse().ok()
+    } else if for_part.matches(':').count() == 1 {
+        for_part.split(':').next()?.parse().ok()
+    } else {
+        for_part.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bracketed_ipv6() {
+        assert_eq!(
+            parse_forwarded_element(r#"for="[2001:db8::1]:8080""#),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_bare_ipv6_without_port() {
+        assert_eq!(
+            parse_forwarded_element("for=2001:db8::1"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_ipv4_with_port() {
+        assert_eq!(
+            parse_forwarded_element("for=192.0.2.60:4711"),
+            Some("192.0.2.60".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_bare_ipv4() {
+        assert_eq!(
+            parse_forwarded_element("for=192.0.2.60"),
+            Some("192.0.2.60".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(parse_forwarded_element("proto=http"), None);
+        assert_eq!(parse_forwarded_element("for=not-an-ip"), None);
+        assert_eq!(parse_forwarded_element(""), None);
+    }
 }
\ No newline at end of file
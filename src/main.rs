@@ -1,8 +1,14 @@
+mod admin;
 mod handlers;
+mod policy;
+mod proxy;
 mod rate_limiter;
 
 use actix_web::{web, App, HttpServer};
-use rate_limiter::RateLimiter;
+use admin::AdminAuth;
+use policy::PolicyStore;
+use proxy::TrustedProxies;
+use rate_limiter::{Algorithm, RateLimiter};
 use std::env;
 use tracing:: info;
 
@@ -22,17 +28,45 @@ async fn main() -> std::io:: Result<()> {
         .parse()
         .expect("PORT must be a number");
 
-    let rate_limiter = RateLimiter:: new(&redis_url)
+    let deferred = env::var("DEFERRED_MODE")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let algorithm = match env::var("ALGORITHM") {
+        Ok(v) if v.eq_ignore_ascii_case("gcra") => Algorithm::Gcra,
+        _ => Algorithm::TokenBucket,
+    };
+    let rate_limiter = if deferred {
+        RateLimiter::new_deferred(&redis_url).await
+    } else {
+        RateLimiter::new(&redis_url, algorithm).await
+    }
+    .expect("Failed to connect to Redis");
+
+    rate_limiter.spawn_health_check();
+
+    let policies = PolicyStore::new(&redis_url)
         .await
-        .expect("Failed to connect to Redis");
+        .expect("Failed to build policy store");
+    policies.spawn_refresh();
+
+    let trusted = TrustedProxies::from_env();
+    let admin_auth = AdminAuth::from_env();
 
     info!("Starting server at http://{}:{}", host, port);
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(rate_limiter.clone()))
+            .app_data(web::Data::new(policies.clone()))
+            .app_data(web::Data::new(trusted.clone()))
+            .app_data(web::Data::new(admin_auth.clone()))
             .route("/check", web::post().to(handlers::check_rate_limit))
             .route("/health", web::get().to(handlers::health))
+            .route("/admin/policy", web::post().to(handlers::set_policy))
+            .route(
+                "/metrics/cardinality",
+                web::get().to(handlers::cardinality),
+            )
     })
     .bind((host. as_str(), port))?
     .run()
@@ -0,0 +1,128 @@
+use crate::rate_limiter::{create_pool_err, pool_err};
+use deadpool_redis::{Config, Pool, Runtime};
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Redis hash holding the per-client tier table, field = client id,
+/// value = `"<requests_per_minute>:<burst>"`.
+const POLICY_HASH_KEY: &str = "ratelimit:policies";
+
+/// How often the in-memory policy table is reloaded from Redis.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Rate-limit tier attached to an identity rather than to the request body.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub requests_per_minute: i64,
+    pub burst: i64,
+}
+
+/// Maps client ids to their tier, backed by a Redis hash and refreshed in the
+/// background. Unknown clients fall back to the configured default tier.
+#[derive(Clone)]
+pub struct PolicyStore {
+    pool: Pool,
+    policies: Arc<RwLock<HashMap<String, Policy>>>,
+    default: Policy,
+}
+
+impl PolicyStore {
+    pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
+        let mut cfg = Config::from_url(redis_url);
+        cfg.pool = Some(deadpool_redis::PoolConfig::new(4));
+        let pool = cfg
+            .create_pool(Some(Runtime::Tokio1))
+            .map_err(create_pool_err)?;
+
+        let store = Self {
+            pool,
+            policies: Arc::new(RwLock::new(HashMap::new())),
+            default: default_policy(),
+        };
+        // Don't let a Redis outage at boot keep the process from starting —
+        // start with an empty table (every client falls back to `default`)
+        // and let `spawn_refresh` pick up the real table once Redis returns.
+        if let Err(e) = store.refresh().await {
+            tracing::warn!(error = %e, "Failed to load policy table at startup; starting with defaults");
+        }
+        Ok(store)
+    }
+
+    /// Resolve the tier for a client, falling back to the default tier.
+    pub fn get(&self, client_id: &str) -> Policy {
+        self.policies
+            .read()
+            .unwrap()
+            .get(client_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Persist a client's tier to Redis and update the local table.
+    pub async fn set(&self, client_id: &str, policy: Policy) -> Result<(), redis::RedisError> {
+        let mut conn = self.pool.get().await.map_err(pool_err)?;
+        let value = format!("{}:{}", policy.requests_per_minute, policy.burst);
+        conn.hset::<_, _, _, ()>(POLICY_HASH_KEY, client_id, value)
+            .await?;
+        self.policies
+            .write()
+            .unwrap()
+            .insert(client_id.to_string(), policy);
+        Ok(())
+    }
+
+    /// Reload the entire tier table from Redis, replacing the local copy.
+    pub async fn refresh(&self) -> Result<(), redis::RedisError> {
+        let mut conn = self.pool.get().await.map_err(pool_err)?;
+        let raw: HashMap<String, String> = conn.hgetall(POLICY_HASH_KEY).await?;
+        let parsed = raw
+            .into_iter()
+            .filter_map(|(id, v)| parse_policy(&v).map(|p| (id, p)))
+            .collect();
+        *self.policies.write().unwrap() = parsed;
+        Ok(())
+    }
+
+    /// Spawn a background task that periodically reloads tiers from Redis.
+    pub fn spawn_refresh(&self) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = store.refresh().await {
+                    tracing::warn!(error = %e, "Failed to refresh policy table");
+                }
+            }
+        });
+    }
+}
+
+/// Read the default tier from `DEFAULT_RPM` / `DEFAULT_BURST`, falling back to a
+/// conservative 60 requests/minute with a matching burst.
+fn default_policy() -> Policy {
+    let requests_per_minute = env::var("DEFAULT_RPM")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    let burst = env::var("DEFAULT_BURST")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(requests_per_minute);
+    Policy {
+        requests_per_minute,
+        burst,
+    }
+}
+
+/// Parse a `"<rpm>:<burst>"` hash value into a [`Policy`].
+fn parse_policy(value: &str) -> Option<Policy> {
+    let (rpm, burst) = value.split_once(':')?;
+    Some(Policy {
+        requests_per_minute: rpm.trim().parse().ok()?,
+        burst: burst.trim().parse().ok()?,
+    })
+}
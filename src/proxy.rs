@@ -0,0 +1,107 @@
+use ipnet::IpNet;
+use std::env;
+use std::net::IpAddr;
+
+/// Set of proxy networks whose `X-Forwarded-For` / `Forwarded` headers we are
+/// willing to trust, loaded from `TRUSTED_PROXIES` (comma-separated CIDRs or
+/// bare addresses). Empty by default, in which case forwarded headers are
+/// ignored entirely and the immediate peer is used.
+#[derive(Clone, Default)]
+pub struct TrustedProxies {
+    cidrs: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Build the trusted set from the `TRUSTED_PROXIES` environment variable.
+    pub fn from_env() -> Self {
+        let cidrs = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(parse_cidr)
+            .collect();
+        Self { cidrs }
+    }
+
+    /// Whether `ip` belongs to any trusted proxy network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Parse a single entry as a CIDR, accepting a bare address as a host route.
+fn parse_cidr(entry: &str) -> Option<IpNet> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return None;
+    }
+    if entry.contains('/') {
+        entry.parse().ok()
+    } else {
+        entry.parse::<IpAddr>().ok().map(IpNet::from)
+    }
+}
+
+/// Resolve the real client IP from a forwarded chain, trusting only headers
+/// added by known proxies. `chain` is the list of addresses in header order
+/// (oldest first); `peer` is the immediate connection peer.
+///
+/// Returns `None` when the peer is not a trusted proxy — in that case the
+/// header is attacker-controlled and must be ignored.
+pub fn resolve_forwarded(
+    trusted: &TrustedProxies,
+    peer: IpAddr,
+    chain: &[IpAddr],
+) -> Option<IpAddr> {
+    if !trusted.contains(peer) {
+        return None;
+    }
+    // Walk the chain from the right, skipping trusted proxies; the first
+    // untrusted address is the furthest hop we can still vouch for.
+    for ip in chain.iter().rev() {
+        if !trusted.contains(*ip) {
+            return Some(*ip);
+        }
+    }
+    // Every hop was a trusted proxy: the original client is the leftmost entry.
+    chain.first().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted_with(cidr: &str) -> TrustedProxies {
+        TrustedProxies {
+            cidrs: vec![cidr.parse().unwrap()],
+        }
+    }
+
+    #[test]
+    fn ignores_chain_from_untrusted_peer() {
+        let trusted = trusted_with("10.0.0.0/8");
+        let peer: IpAddr = "203.0.113.5".parse().unwrap();
+        let chain = vec!["198.51.100.1".parse().unwrap()];
+        assert_eq!(resolve_forwarded(&trusted, peer, &chain), None);
+    }
+
+    #[test]
+    fn trusted_peer_with_spoofed_chain_returns_furthest_untrusted_hop() {
+        let trusted = trusted_with("10.0.0.0/8");
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        // An attacker-controlled client could prepend arbitrary addresses;
+        // only the untrusted tail of the chain is believed.
+        let spoofed: IpAddr = "1.2.3.4".parse().unwrap();
+        let client: IpAddr = "198.51.100.7".parse().unwrap();
+        let chain = vec![spoofed, client, "10.0.0.2".parse().unwrap()];
+        assert_eq!(resolve_forwarded(&trusted, peer, &chain), Some(client));
+    }
+
+    #[test]
+    fn all_trusted_chain_returns_leftmost_entry() {
+        let trusted = trusted_with("10.0.0.0/8");
+        let peer: IpAddr = "10.0.0.3".parse().unwrap();
+        let original_client: IpAddr = "10.0.0.1".parse().unwrap();
+        let chain = vec![original_client, "10.0.0.2".parse().unwrap()];
+        assert_eq!(resolve_forwarded(&trusted, peer, &chain), Some(original_client));
+    }
+}
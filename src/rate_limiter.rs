@@ -1,11 +1,70 @@
-use redis::{aio::MultiplexedConnection, AsyncCommands, Client, Script};
+use deadpool_redis::{Config, Pool, PoolError, Runtime};
+use moka::future::Cache;
+use redis::Script;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync:: Mutex;
+use std::time::Duration;
+
+/// Default pool size used when `REDIS_MAX_CONNECTIONS` is unset or unparsable.
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+
+/// How often the background task re-pings Redis to refresh the health flag.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Key prefix for the per-minute HyperLogLog structures tracking distinct
+/// clients. Buckets are suffixed with `:min:<epoch_minute>`; the rolling hour
+/// figure is derived from the last 60 of these rather than a separate bucket.
+const HLL_PREFIX: &str = "ratelimit:hll";
+
+/// Approximate distinct-client counts over recent rolling windows.
+#[derive(Debug, Clone, Copy)]
+pub struct Cardinality {
+    pub last_minute: u64,
+    pub last_hour: u64,
+}
+
+/// Bounded capacity of the in-process front cache used in deferred mode.
+const LOCAL_CACHE_CAPACITY: u64 = 100_000;
+
+/// How long a locally estimated bucket is trusted before Redis is reconciled.
+const LOCAL_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Locally estimated token-bucket state for a single client. Between Redis
+/// reconciliations we refill and decrement this copy in process so hot clients
+/// skip the round-trip entirely.
+#[derive(Clone)]
+struct CachedState {
+    tokens: f64,
+    last_refill_ms: i64,
+    max_tokens: i64,
+    refill_rate: f64,
+    /// When this entry was last seeded from Redis; local admission stops once
+    /// `LOCAL_CACHE_TTL` has passed since then, even if the entry is still hot.
+    reconciled_at_ms: i64,
+}
+
+/// Rate-limiting algorithm backing a [`RateLimiter`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Token bucket — `(tokens, last_refill)` per key (the default).
+    #[default]
+    TokenBucket,
+    /// Generic Cell Rate Algorithm — a single TAT value per key, smooth pacing.
+    Gcra,
+}
 
 #[derive(Clone)]
 pub struct RateLimiter {
-    conn: Arc<Mutex<MultiplexedConnection>>,
+    pool: Pool,
+    algorithm: Algorithm,
     script: Arc<Script>,
+    /// Present only in deferred mode; fronts Redis with a bounded local cache.
+    cache: Option<Cache<String, CachedState>>,
+    /// Tracks whether Redis is currently reachable, refreshed in the background.
+    healthy: Arc<AtomicBool>,
+    /// When true, Redis failures are treated as "allow"; otherwise as "deny".
+    fail_open: bool,
 }
 
 #[derive(Debug)]
@@ -16,25 +75,163 @@ pub struct RateLimitResult {
 }
 
 impl RateLimiter {
-    pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
-        let client = Client::open(redis_url)?;
-        let conn = client.get_multiplexed_tokio_connection().await?;
+    pub async fn new(redis_url: &str, algorithm: Algorithm) -> Result<Self, redis::RedisError> {
+        let mut cfg = Config::from_url(redis_url);
+        cfg.pool = Some(deadpool_redis::PoolConfig::new(max_connections()));
+        let pool = cfg
+            .create_pool(Some(Runtime::Tokio1))
+            .map_err(create_pool_err)?;
 
-        let script = Script::new(LUA_SCRIPT);
+        let script = Script::new(match algorithm {
+            Algorithm::TokenBucket => LUA_SCRIPT,
+            Algorithm::Gcra => GCRA_SCRIPT,
+        });
 
         Ok(Self {
-            conn:  Arc::new(Mutex::new(conn)),
+            pool,
+            algorithm,
             script:  Arc::new(script),
+            cache: None,
+            healthy: Arc::new(AtomicBool::new(true)),
+            fail_open: fail_open_policy(),
         })
     }
 
+    /// Build a limiter in deferred mode: Redis stays the source of truth, but a
+    /// bounded in-process cache absorbs the bulk of requests from hot clients.
+    ///
+    /// Caveat: admission decisions served from the cache are made per-process
+    /// with no cross-instance coordination beyond the cache's 1s TTL. Behind a
+    /// horizontally scaled deployment, each replica can independently admit up
+    /// to `burst` requests per client within that window, so the effective
+    /// limit scales with instance count. Only enable this under a single
+    /// instance, or where that slack is acceptable.
+    pub async fn new_deferred(redis_url: &str) -> Result<Self, redis::RedisError> {
+        let mut limiter = Self::new(redis_url, Algorithm::TokenBucket).await?;
+        limiter.cache = Some(
+            Cache::builder()
+                .max_capacity(LOCAL_CACHE_CAPACITY)
+                .time_to_live(LOCAL_CACHE_TTL)
+                .build(),
+        );
+        Ok(limiter)
+    }
+
     pub async fn check(
         &self,
         client_id: &str,
         requests_per_minute:  i64,
         burst: i64,
     ) -> Result<RateLimitResult, redis::RedisError> {
+        match &self.cache {
+            Some(cache) => {
+                self.check_deferred(cache, client_id, requests_per_minute, burst)
+                    .await
+            }
+            None => self.check_redis(client_id, requests_per_minute, burst).await,
+        }
+    }
+
+    /// Authoritative check via the configured algorithm's Lua script on a
+    /// pooled connection.
+    async fn check_redis(
+        &self,
+        client_id: &str,
+        requests_per_minute:  i64,
+        burst: i64,
+    ) -> Result<RateLimitResult, redis::RedisError> {
+        let max_tokens = burst.max(1);
+
+        // Redis is known to be down: skip the attempt and its slow timeout.
+        if !self.healthy.load(Ordering::Relaxed) {
+            return Ok(self.degraded_result(max_tokens));
+        }
+
         let key = format!("ratelimit:{}", client_id);
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std:: time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        let outcome = async {
+            let mut conn = self.pool.get().await.map_err(pool_err)?;
+            let mut invocation = self.script.key(&key);
+            match self.algorithm {
+                Algorithm::TokenBucket => {
+                    let refill_rate = requests_per_minute as f64 / 60.0;
+                    invocation.arg(max_tokens).arg(refill_rate).arg(now_ms);
+                }
+                Algorithm::Gcra => {
+                    // Emission interval and burst tolerance, both in ms.
+                    let emission_interval = 60_000.0 / requests_per_minute.max(1) as f64;
+                    let tau = (max_tokens - 1) as f64 * emission_interval;
+                    invocation.arg(now_ms).arg(emission_interval).arg(tau);
+                }
+            }
+            let result: Vec<i64> = invocation.invoke_async(&mut *conn).await?;
+            // Best-effort cardinality tracking; never fail the request over it.
+            let _ = record_cardinality(&mut conn, client_id, now_ms).await;
+            Ok::<_, redis::RedisError>(result)
+        }
+        .await;
+
+        match outcome {
+            Ok(result) => {
+                self.healthy.store(true, Ordering::Relaxed);
+                Ok(RateLimitResult {
+                    allowed: result[0] == 1,
+                    remaining:  result[1],
+                    retry_after_ms: result[2],
+                })
+            }
+            Err(e) => {
+                // Mark degraded so the background probe and hot path agree.
+                self.healthy.store(false, Ordering::Relaxed);
+                tracing::warn!(
+                    error = %e,
+                    fail_open = self.fail_open,
+                    "Redis unavailable; applying fail-{} policy",
+                    if self.fail_open { "open" } else { "closed" }
+                );
+                Ok(self.degraded_result(max_tokens))
+            }
+        }
+    }
+
+    /// Synthesized result used while Redis is unreachable, per the configured
+    /// fail-open / fail-closed policy.
+    fn degraded_result(&self, max_tokens: i64) -> RateLimitResult {
+        synthesize_degraded_result(self.fail_open, max_tokens)
+    }
+
+    /// Returns the last observed Redis connectivity without issuing a command.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Spawn a background task that periodically pings Redis and updates the
+    /// shared health flag so `/health` and the hot path see real connectivity.
+    pub fn spawn_health_check(&self) {
+        let limiter = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let ok = limiter.ping().await.is_ok();
+                limiter.healthy.store(ok, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// Serve from the local estimate when a client has headroom; reconcile with
+    /// Redis when the estimate runs low or the cached entry has expired.
+    async fn check_deferred(
+        &self,
+        cache: &Cache<String, CachedState>,
+        client_id: &str,
+        requests_per_minute:  i64,
+        burst: i64,
+    ) -> Result<RateLimitResult, redis::RedisError> {
         let max_tokens = burst.max(1);
         let refill_rate = requests_per_minute as f64 / 60.0;
         let now_ms = std::time::SystemTime::now()
@@ -42,28 +239,151 @@ impl RateLimiter {
             .unwrap()
             .as_millis() as i64;
 
-        let mut conn = self. conn.lock().await;
+        if let Some(mut state) = cache.get(client_id).await {
+            // Local re-inserts restart moka's TTL, so age is tracked explicitly.
+            let fresh = now_ms - state.reconciled_at_ms < LOCAL_CACHE_TTL.as_millis() as i64;
+            let elapsed_ms = (now_ms - state.last_refill_ms).max(0);
+            let refilled = state.tokens + (elapsed_ms as f64 / 1000.0) * state.refill_rate;
+            state.tokens = f64::min(state.max_tokens as f64, refilled);
+            state.last_refill_ms = now_ms;
 
-        let result:  Vec<i64> = self
-            .script
-            .key(&key)
-            .arg(max_tokens)
-            .arg(refill_rate)
-            .arg(now_ms)
-            .invoke_async(&mut *conn)
-            .await?;
+            // Only trust the local copy while it is within its TTL and can still
+            // serve a request.
+            if fresh && state.tokens >= 1.0 {
+                state.tokens -= 1.0;
+                let remaining = state.tokens.floor() as i64;
+                cache.insert(client_id.to_string(), state).await;
+                return Ok(RateLimitResult {
+                    allowed: true,
+                    remaining,
+                    retry_after_ms: 0,
+                });
+            }
+        }
 
-        Ok(RateLimitResult {
-            allowed: result[0] == 1,
-            remaining:  result[1],
-            retry_after_ms: result[2],
-        })
+        // Cache miss, expired TTL, or drained estimate: go to the authority.
+        let result = self.check_redis(client_id, requests_per_minute, burst).await?;
+        cache
+            .insert(
+                client_id.to_string(),
+                CachedState {
+                    tokens: result.remaining as f64,
+                    last_refill_ms: now_ms,
+                    max_tokens,
+                    refill_rate,
+                    reconciled_at_ms: now_ms,
+                },
+            )
+            .await;
+        Ok(result)
     }
 
     pub async fn ping(&self) -> Result<(), redis::RedisError> {
-        let mut conn = self.conn.lock().await;
+        let mut conn = self.pool.get().await.map_err(pool_err)?;
         redis::cmd("PING").query_async(&mut *conn).await
     }
+
+    /// Report approximate distinct-client counts for the current minute and the
+    /// rolling last hour. The hour figure is the union cardinality of the last
+    /// 60 minute buckets (what `PFMERGE` + `PFCOUNT` would yield), obtained in a
+    /// single multi-key `PFCOUNT`.
+    pub async fn cardinality(&self) -> Result<Cardinality, redis::RedisError> {
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std:: time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let minute = now_ms / 60_000;
+        let mut conn = self.pool.get().await.map_err(pool_err)?;
+
+        let last_minute: u64 = redis::cmd("PFCOUNT")
+            .arg(format!("{}:min:{}", HLL_PREFIX, minute))
+            .query_async(&mut *conn)
+            .await?;
+
+        let mut count = redis::cmd("PFCOUNT");
+        for i in 0..60 {
+            count.arg(format!("{}:min:{}", HLL_PREFIX, minute - i));
+        }
+        let last_hour: u64 = count.query_async(&mut *conn).await?;
+
+        Ok(Cardinality {
+            last_minute,
+            last_hour,
+        })
+    }
+}
+
+/// Resolve the configured pool size from `REDIS_MAX_CONNECTIONS`.
+fn max_connections() -> usize {
+    env::var("REDIS_MAX_CONNECTIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS)
+}
+
+/// Add a client to the current minute's HyperLogLog bucket. The TTL is
+/// generous so the rolling last-hour scan in [`RateLimiter::cardinality`]
+/// always has data to merge.
+async fn record_cardinality(
+    conn: &mut deadpool_redis::Connection,
+    client_id: &str,
+    now_ms: i64,
+) -> Result<(), redis::RedisError> {
+    let min_key = format!("{}:min:{}", HLL_PREFIX, now_ms / 60_000);
+    let mut pipe = redis::pipe();
+    pipe.cmd("PFADD").arg(&min_key).arg(client_id).ignore();
+    pipe.cmd("EXPIRE").arg(&min_key).arg(7_200).ignore();
+    pipe.query_async(&mut **conn).await
+}
+
+/// Synthesize a degraded-mode result for the given fail-open/fail-closed
+/// policy. Pulled out of [`RateLimiter::degraded_result`] so the branch logic
+/// is testable without standing up a pool.
+fn synthesize_degraded_result(fail_open: bool, max_tokens: i64) -> RateLimitResult {
+    if fail_open {
+        RateLimitResult {
+            allowed: true,
+            remaining: (max_tokens - 1).max(0),
+            retry_after_ms: 0,
+        }
+    } else {
+        RateLimitResult {
+            allowed: false,
+            remaining: 0,
+            retry_after_ms: 1000,
+        }
+    }
+}
+
+/// Resolve the failure policy: fail-open by default, fail-closed when
+/// `FAIL_CLOSED` is set to a truthy value.
+fn fail_open_policy() -> bool {
+    match env::var("FAIL_CLOSED") {
+        Ok(v) => !(v == "1" || v.eq_ignore_ascii_case("true")),
+        Err(_) => true,
+    }
+}
+
+/// Collapse a pool checkout failure into the `redis::RedisError` the rest of
+/// the crate already threads through, preserving the backend error verbatim.
+pub(crate) fn pool_err(err: PoolError) -> redis::RedisError {
+    match err {
+        PoolError::Backend(e) => e,
+        other => redis::RedisError::from((
+            redis::ErrorKind::IoError,
+            "redis pool error",
+            other.to_string(),
+        )),
+    }
+}
+
+pub(crate) fn create_pool_err(err: deadpool_redis::CreatePoolError) -> redis::RedisError {
+    redis::RedisError::from((
+        redis::ErrorKind::IoError,
+        "failed to build redis pool",
+        err.to_string(),
+    ))
 }
 
 const LUA_SCRIPT: &str = r#"
@@ -103,6 +423,38 @@ redis.call('EXPIRE', key, 120)
 return {allowed, math.floor(tokens), retry_after_ms}
 "#;
 
+const GCRA_SCRIPT: &str = r#"
+local key = KEYS[1]
+local now_ms = tonumber(ARGV[1])
+local t = tonumber(ARGV[2])
+local tau = tonumber(ARGV[3])
+
+local tat = tonumber(redis.call('GET', key))
+if tat == nil then
+    tat = now_ms
+end
+
+local allow_at = tat - tau
+local allowed = 0
+local remaining = 0
+local retry_after_ms = 0
+
+if now_ms >= allow_at then
+    allowed = 1
+    local new_tat = math.max(tat, now_ms) + t
+    redis.call('SET', key, new_tat)
+    redis.call('PEXPIRE', key, math.ceil(tau + t))
+    remaining = math.floor((now_ms + tau - new_tat) / t) + 1
+    if remaining < 0 then
+        remaining = 0
+    end
+else
+    retry_after_ms = math.ceil(allow_at - now_ms)
+end
+
+return {allowed, remaining, retry_after_ms}
+"#;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,10 +495,98 @@ mod tests {
     fn test_retry_after_calculation() {
         let tokens:  f64 = 0.0;
         let refill_rate: f64 = 1.0;
-        
+
         let tokens_needed = 1.0 - tokens;
         let retry_after_ms = ((tokens_needed / refill_rate) * 1000.0).ceil() as i64;
-        
+
         assert_eq!(retry_after_ms, 1000);
     }
+
+    #[test]
+    fn test_degraded_result_fail_open() {
+        let result = synthesize_degraded_result(true, 10);
+        assert!(result.allowed);
+        assert_eq!(result.remaining, 9);
+        assert_eq!(result.retry_after_ms, 0);
+    }
+
+    #[test]
+    fn test_degraded_result_fail_closed() {
+        let result = synthesize_degraded_result(false, 10);
+        assert!(!result.allowed);
+        assert_eq!(result.remaining, 0);
+        assert_eq!(result.retry_after_ms, 1000);
+    }
+
+    #[test]
+    fn test_deferred_cache_refill_and_drain() {
+        // Mirrors the refill/decrement arithmetic in `check_deferred`.
+        let refill = |state: &mut CachedState, now_ms: i64| {
+            let elapsed_ms = (now_ms - state.last_refill_ms).max(0);
+            let refilled = state.tokens + (elapsed_ms as f64 / 1000.0) * state.refill_rate;
+            state.tokens = f64::min(state.max_tokens as f64, refilled);
+            state.last_refill_ms = now_ms;
+        };
+
+        let mut state = CachedState {
+            tokens: 0.0,
+            last_refill_ms: 0,
+            max_tokens: 5,
+            refill_rate: 1.0, // 1 token/second
+            reconciled_at_ms: 0,
+        };
+
+        // Well past the TTL: refill should cap at max_tokens, not overshoot.
+        refill(&mut state, 60_000);
+        assert_eq!(state.tokens, 5.0);
+
+        // Drain below 1 token: the local estimate can no longer serve a request.
+        state.tokens = 0.4;
+        state.last_refill_ms = 60_000;
+        refill(&mut state, 60_000);
+        assert!(state.tokens < 1.0, "drained estimate should not admit locally");
+
+        // A fresh reconcile from Redis reseeds the cache with the authoritative
+        // remaining count and resets the refill clock.
+        let reconciled = CachedState {
+            tokens: 3.0,
+            last_refill_ms: 61_000,
+            max_tokens: state.max_tokens,
+            refill_rate: state.refill_rate,
+            reconciled_at_ms: 61_000,
+        };
+        assert_eq!(reconciled.tokens, 3.0);
+        assert_eq!(reconciled.last_refill_ms, 61_000);
+    }
+
+    #[test]
+    fn test_gcra_logic() {
+        // 10 requests/minute, burst of 3: emission interval t = 6000ms,
+        // tau = (burst - 1) * t = 12000ms.
+        let t: f64 = 6000.0;
+        let tau: f64 = 12000.0;
+
+        let mut tat: f64 = 0.0;
+
+        let consume = |tat: &mut f64, now_ms: f64| -> bool {
+            let allow_at = *tat - tau;
+            if now_ms >= allow_at {
+                *tat = f64::max(*tat, now_ms) + t;
+                true
+            } else {
+                false
+            }
+        };
+
+        // The burst of 3 should be allowed back-to-back at t=0.
+        assert!(consume(&mut tat, 0.0), "1st request should be allowed");
+        assert!(consume(&mut tat, 0.0), "2nd request should be allowed");
+        assert!(consume(&mut tat, 0.0), "3rd request should be allowed");
+
+        // The burst is exhausted; a 4th immediate request is rejected.
+        assert!(!consume(&mut tat, 0.0), "4th request should be blocked");
+
+        // After the emission interval elapses, the next request is allowed.
+        assert!(consume(&mut tat, t), "Should allow after one emission interval");
+    }
 }
\ No newline at end of file